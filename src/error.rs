@@ -7,6 +7,10 @@ use std::result;
 #[derive(Copy, Clone, Debug)]
 pub enum Error {
     PrepareLooperFailed,
+    PollFailed,
+    AddFdFailed,
+    RemoveFdFailed,
+    EventFdFailed,
 }
 
 pub type Result<T> = result::Result<T, Error>;
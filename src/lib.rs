@@ -38,22 +38,40 @@
 //! In such cases the method calls themselves may return errors when called on expired or invalid
 //! handle.
 //!
+//! ## Loopers are thread-local
+//!
+//! A looper belongs to the thread that prepared it. Operations that drive the loop (polling) are
+//! only valid on that thread, while waking it or attaching file descriptors may be done from any
+//! thread. This distinction is reflected in the type system: `prepare()` hands out a `ThreadLooper`
+//! that is `!Send + !Sync` and carries the polling methods, and `ThreadLooper::as_foreign` hands
+//! out a `ForeignLooper` that is `Send + Sync` for the cross-thread operations.
+//!
 
 extern crate android_looper_sys as ffi;
 extern crate libc;
 
 use self::error::{Error, Result};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::os::unix::io::RawFd;
 use std::ptr;
-use libc::c_int;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use libc::{c_int, c_void};
 
 pub use ffi::LooperPrepareOpts;
 
 pub mod error;
+pub mod looper_channel;
 
 pub type LooperHandle = *mut ffi::ALooper;
 
 /**
-Reference to a looper.
+The looper of the current thread.
+
+A looper belongs to the thread that prepared it, and operations such as polling are only valid on
+that thread. `ThreadLooper` is therefore `!Send + !Sync`: it can only be obtained on, and used
+from, its owning thread.
 
 From [NDK docs](http://developer.android.com/ndk/reference/group___looper.html):
 
@@ -66,36 +84,78 @@ From [NDK docs](http://developer.android.com/ndk/reference/group___looper.html):
 
 > A thread can have only one Looper associated with it.
 */
-#[derive(Copy, Clone, Debug)]
-pub struct LooperRef {
-    handle: LooperHandle,
+pub struct ThreadLooper {
+    foreign: ForeignLooper,
+    _marker: PhantomData<*mut ()>,
 }
 
-impl LooperRef {
-    /// Create `LooperRef` from native handle.
-    pub fn from_handle(handle: LooperHandle) -> LooperRef {
-        LooperRef { handle: handle }
-    }
-
+impl ThreadLooper {
     /// Prepares a looper associated with the calling thread, and returns it.
     /// If the thread already has a looper, it is returned. Otherwise, a new one is created,
     /// associated with the thread, and returned.
-    pub fn prepare(opts: LooperPrepareOpts) -> Result<LooperRef> {
+    pub fn prepare(opts: LooperPrepareOpts) -> Result<ThreadLooper> {
         let looper_handle = unsafe { ffi::ALooper_prepare(opts as c_int) };
         if looper_handle.is_null() {
             return Err(Error::PrepareLooperFailed);
         }
-        Ok(LooperRef { handle: looper_handle })
+        Ok(ThreadLooper::from_foreign(ForeignLooper::from_handle(looper_handle)))
     }
 
-    /// Acquire looper to prevent its deletion until `AcquiredLooper` object is dropped.
-    pub fn acquire(&self) -> AcquiredLooper {
-        AcquiredLooper::from_ref(*self)
+    /// Returns the looper associated with the calling thread, if any.
+    ///
+    /// Calls `ALooper_forThread`.
+    pub fn for_thread() -> Option<ThreadLooper> {
+        let looper_handle = unsafe { ffi::ALooper_forThread() };
+        if looper_handle.is_null() {
+            None
+        } else {
+            Some(ThreadLooper::from_foreign(ForeignLooper::from_handle(looper_handle)))
+        }
+    }
+
+    fn from_foreign(foreign: ForeignLooper) -> ThreadLooper {
+        ThreadLooper { foreign: foreign, _marker: PhantomData }
+    }
+
+    /// Get the sendable reference to this looper, valid to use from any thread.
+    pub fn as_foreign(&self) -> ForeignLooper {
+        self.foreign
     }
 
     /// Get native looper handle.
     pub fn handle(&self) -> LooperHandle {
-        self.handle
+        self.foreign.handle()
+    }
+
+    /// Waits for events to be available, with optional timeout, and returns once a single event
+    /// has been handled.
+    ///
+    /// Calls `ALooper_pollOnce`. A `timeout` of `None` blocks indefinitely, `Some(Duration::new(0,
+    /// 0))` returns immediately, and any other duration is truncated to whole milliseconds
+    /// (saturating to `i32::MAX` milliseconds). When an identifier-based file descriptor is ready,
+    /// its out-params are returned via `Poll::Event`.
+    pub fn poll_once(&self, timeout: Option<Duration>) -> Result<Poll> {
+        let mut fd: c_int = 0;
+        let mut events: c_int = 0;
+        let mut data: *mut c_void = ptr::null_mut();
+        let ident = unsafe {
+            ffi::ALooper_pollOnce(timeout_to_millis(timeout), &mut fd, &mut events, &mut data)
+        };
+        poll_result(ident, fd, events, data)
+    }
+
+    /// Like [`poll_once`](#method.poll_once) but keeps handling ready callbacks until there are no
+    /// more, returning the last non-callback result.
+    ///
+    /// Calls `ALooper_pollAll`.
+    pub fn poll_all(&self, timeout: Option<Duration>) -> Result<Poll> {
+        let mut fd: c_int = 0;
+        let mut events: c_int = 0;
+        let mut data: *mut c_void = ptr::null_mut();
+        let ident = unsafe {
+            ffi::ALooper_pollAll(timeout_to_millis(timeout), &mut fd, &mut events, &mut data)
+        };
+        poll_result(ident, fd, events, data)
     }
 
     /// Performs all pending callbacks until all data has been consumed.
@@ -107,6 +167,329 @@ impl LooperRef {
     }
 }
 
+/// Convert an optional poll `timeout` to the millisecond `c_int` expected by the native poll
+/// functions: `None` blocks forever (`-1`), `Some(Duration::new(0, 0))` is non-blocking (`0`), and
+/// longer durations saturate at `i32::MAX` milliseconds.
+fn timeout_to_millis(timeout: Option<Duration>) -> c_int {
+    match timeout {
+        None => -1,
+        Some(duration) => {
+            let millis = duration.as_secs()
+                .saturating_mul(1000)
+                .saturating_add((duration.subsec_nanos() / 1_000_000) as u64);
+            if millis > c_int::MAX as u64 {
+                c_int::MAX
+            } else {
+                millis as c_int
+            }
+        }
+    }
+}
+
+/// Interpret the identifier returned by `ALooper_pollOnce`/`ALooper_pollAll` and its out-params.
+fn poll_result(ident: c_int, fd: c_int, events: c_int, data: *mut c_void) -> Result<Poll> {
+    match ident {
+        ffi::ALOOPER_POLL_WAKE => Ok(Poll::Wake),
+        ffi::ALOOPER_POLL_CALLBACK => Ok(Poll::Callback),
+        ffi::ALOOPER_POLL_TIMEOUT => Ok(Poll::Timeout),
+        ffi::ALOOPER_POLL_ERROR => Err(Error::PollFailed),
+        ident => Ok(Poll::Event {
+            ident: ident,
+            fd: fd as RawFd,
+            events: FdEvent::from_bits_truncate(events),
+            data: data,
+        }),
+    }
+}
+
+/// Result of a [`ThreadLooper`] poll, mirroring the native `ALOOPER_POLL_*` return codes.
+#[derive(Copy, Clone, Debug)]
+pub enum Poll {
+    /// The poll was woken using [`ForeignLooper::wake`] before the timeout expired.
+    Wake,
+    /// One or more callbacks were executed.
+    Callback,
+    /// The timeout expired before any events became available.
+    Timeout,
+    /// An identifier-based file descriptor is ready. The out-params describe which one.
+    Event {
+        /// The identifier the file descriptor was registered under.
+        ident: c_int,
+        /// The ready file descriptor.
+        fd: RawFd,
+        /// The events that occurred on `fd`.
+        events: FdEvent,
+        /// The data pointer the file descriptor was registered with.
+        data: *mut c_void,
+    },
+}
+
+/// Events that may be reported for, or requested on, a registered file descriptor.
+///
+/// These mirror the native `ALOOPER_EVENT_*` flags and may be combined with `|`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FdEvent {
+    bits: c_int,
+}
+
+impl FdEvent {
+    /// The file descriptor is readable (`ALOOPER_EVENT_INPUT`).
+    pub const INPUT: FdEvent = FdEvent { bits: ffi::ALOOPER_EVENT_INPUT };
+    /// The file descriptor is writable (`ALOOPER_EVENT_OUTPUT`).
+    pub const OUTPUT: FdEvent = FdEvent { bits: ffi::ALOOPER_EVENT_OUTPUT };
+    /// An error occurred on the file descriptor (`ALOOPER_EVENT_ERROR`).
+    pub const ERROR: FdEvent = FdEvent { bits: ffi::ALOOPER_EVENT_ERROR };
+    /// The remote end of the file descriptor hung up (`ALOOPER_EVENT_HANGUP`).
+    pub const HANGUP: FdEvent = FdEvent { bits: ffi::ALOOPER_EVENT_HANGUP };
+    /// The file descriptor is invalid (`ALOOPER_EVENT_INVALID`).
+    pub const INVALID: FdEvent = FdEvent { bits: ffi::ALOOPER_EVENT_INVALID };
+
+    /// An empty set of events.
+    pub fn empty() -> FdEvent {
+        FdEvent { bits: 0 }
+    }
+
+    /// Build an `FdEvent` from raw native bits, dropping any bits that are not known flags.
+    pub fn from_bits_truncate(bits: c_int) -> FdEvent {
+        let known = FdEvent::INPUT.bits | FdEvent::OUTPUT.bits | FdEvent::ERROR.bits
+            | FdEvent::HANGUP.bits | FdEvent::INVALID.bits;
+        FdEvent { bits: bits & known }
+    }
+
+    /// Get the raw native bits.
+    pub fn bits(&self) -> c_int {
+        self.bits
+    }
+
+    /// Returns `true` if all of the flags in `other` are contained within `self`.
+    pub fn contains(&self, other: FdEvent) -> bool {
+        self.bits & other.bits == other.bits
+    }
+}
+
+impl std::ops::BitOr for FdEvent {
+    type Output = FdEvent;
+
+    fn bitor(self, rhs: FdEvent) -> FdEvent {
+        FdEvent { bits: self.bits | rhs.bits }
+    }
+}
+
+/**
+Reference to a looper, valid to use from any thread.
+
+This wraps a looper handle owned by some thread and exposes the operations that any thread may
+perform, such as waking it or attaching file descriptors. It is `Copy`, `Send` and `Sync`; the
+native looper lifetime is controlled elsewhere, so method calls may fail when the handle is no
+longer valid.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct ForeignLooper {
+    handle: LooperHandle,
+}
+
+unsafe impl Send for ForeignLooper {}
+unsafe impl Sync for ForeignLooper {}
+
+impl ForeignLooper {
+    /// Create `ForeignLooper` from native handle.
+    pub fn from_handle(handle: LooperHandle) -> ForeignLooper {
+        ForeignLooper { handle: handle }
+    }
+
+    /// Acquire looper to prevent its deletion until `AcquiredLooper` object is dropped.
+    pub fn acquire(&self) -> AcquiredLooper {
+        AcquiredLooper::from_ref(*self)
+    }
+
+    /// Wake up a looper blocked in a poll on its owning thread.
+    ///
+    /// Calls `ALooper_wake`. A concurrent [`ThreadLooper::poll_once`] or
+    /// [`ThreadLooper::poll_all`] returns `Poll::Wake`. This is the primitive a worker thread uses
+    /// to signal the looper thread that new work is available.
+    pub fn wake(&self) {
+        unsafe { ffi::ALooper_wake(self.handle) };
+    }
+
+    /// Attach a file descriptor to the looper under the given identifier.
+    ///
+    /// Calls `ALooper_addFd` with a null callback, so readiness is surfaced through
+    /// [`ThreadLooper::poll_once`] as `Poll::Event` carrying this `ident`. The `events` select
+    /// which conditions are reported.
+    pub fn add_fd(&self, fd: RawFd, ident: c_int, events: FdEvent) -> Result<()> {
+        let status = unsafe {
+            ffi::ALooper_addFd(self.handle, fd, ident, events.bits(), None, ptr::null_mut())
+        };
+        if status == 1 {
+            Ok(())
+        } else {
+            Err(Error::AddFdFailed)
+        }
+    }
+
+    /// Attach a file descriptor to the looper, invoking `callback` on the owning thread whenever
+    /// it becomes ready.
+    ///
+    /// Calls `ALooper_addFd` with a callback, so the file descriptor is handled internally and
+    /// reported through `Poll::Callback` rather than `Poll::Event`. The callback returns `true` to
+    /// keep receiving events or `false` to unregister the descriptor (matching
+    /// `ALooper_callbackFunc` semantics), in which case its backing box is freed. Re-registering
+    /// the same descriptor replaces, and frees, any previous callback.
+    ///
+    /// The backing box is freed when the callback unregisters itself, when [`remove_fd`] is
+    /// called, or when the descriptor is re-registered. It is **not** freed when the looper goes
+    /// away: `ForeignLooper` is `Copy` and holds no ownership of the native looper, so a callback
+    /// that is never unregistered lives for the lifetime of the process. Call [`remove_fd`] before
+    /// discarding a looper to release its callbacks.
+    ///
+    /// [`remove_fd`]: #method.remove_fd
+    pub fn add_fd_with_callback<F>(&self, fd: RawFd, events: FdEvent, callback: F) -> Result<()>
+        where F: FnMut(RawFd, FdEvent) -> bool + Send + 'static
+    {
+        let shared: SharedFdCallback = Arc::new(Mutex::new(Box::new(callback)));
+        // Hand the native side its own strong reference as the `data` pointer; `remove_fd` and the
+        // trampoline both reconstruct it from the registry to reclaim it exactly once.
+        let data = Arc::into_raw(shared.clone());
+        let key = (self.handle as usize, fd);
+        // Record the new registration, keeping any displaced one, but do not reclaim it yet: the
+        // native looper still points at the old `data` until `ALooper_addFd` swaps it below.
+        let old = callback_registry()
+            .lock()
+            .unwrap()
+            .insert(key, Registration { callback: shared, data: data });
+        let status = unsafe {
+            ffi::ALooper_addFd(
+                self.handle,
+                fd,
+                ffi::ALOOPER_POLL_CALLBACK,
+                events.bits(),
+                Some(fd_callback_trampoline),
+                data as *mut c_void,
+            )
+        };
+        if status == 1 {
+            // The native looper now references the new registration, so the old one is safe to
+            // reclaim.
+            if let Some(old) = old {
+                drop_registration(old);
+            }
+            Ok(())
+        } else {
+            // The swap failed and the native looper still references the old registration (if
+            // any). Restore it, remove the new one we inserted, and reclaim only the new one.
+            let displaced = {
+                let mut registry = callback_registry().lock().unwrap();
+                match old {
+                    Some(old) => registry.insert(key, old),
+                    None => registry.remove(&key),
+                }
+            };
+            if let Some(new) = displaced {
+                drop_registration(new);
+            }
+            Err(Error::AddFdFailed)
+        }
+    }
+
+    /// Remove a previously attached file descriptor.
+    ///
+    /// Calls `ALooper_removeFd` and frees the boxed callback, if any, registered for `fd`.
+    pub fn remove_fd(&self, fd: RawFd) -> Result<()> {
+        let status = unsafe { ffi::ALooper_removeFd(self.handle, fd) };
+        let removed = callback_registry()
+            .lock()
+            .unwrap()
+            .remove(&(self.handle as usize, fd));
+        if let Some(registration) = removed {
+            drop_registration(registration);
+        }
+        if status == -1 {
+            Err(Error::RemoveFdFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get native looper handle.
+    pub fn handle(&self) -> LooperHandle {
+        self.handle
+    }
+}
+
+/// A boxed file-descriptor callback.
+type BoxedFdCallback = Box<dyn FnMut(RawFd, FdEvent) -> bool + Send>;
+
+/// A callback shared between the registry and the native side. The `Mutex` serializes the
+/// in-flight trampoline call against teardown, and the `Arc` keeps the box alive for as long as
+/// either side still references it, so a concurrent `remove_fd` can never free it out from under a
+/// running callback.
+type SharedFdCallback = Arc<Mutex<BoxedFdCallback>>;
+
+/// A registered callback, holding the registry's reference alongside the raw pointer handed to the
+/// native side so the latter can be reclaimed exactly once.
+struct Registration {
+    callback: SharedFdCallback,
+    data: *const Mutex<BoxedFdCallback>,
+}
+
+// The `data` pointer is reclaimed back into an `Arc` only by whoever removed the registration from
+// the registry, so exactly once; moving the bookkeeping across threads is sound because the
+// pointed-to allocation is reference counted.
+unsafe impl Send for Registration {}
+
+/// Reclaim the native-side reference of a registration removed from the registry.
+///
+/// First wait on the callback mutex so any in-flight trampoline has finished executing, then drop
+/// the native reference. The allocation is only actually freed once every outstanding reference
+/// (including one briefly held by a running trampoline) is gone.
+fn drop_registration(registration: Registration) {
+    { let _guard = registration.callback.lock().unwrap(); }
+    unsafe { drop(Arc::from_raw(registration.data)); }
+}
+
+/// Registry of boxed callbacks keyed by `(looper handle, fd)`, so they can be freed when the
+/// descriptor is removed or re-registered rather than leaked into the native side.
+fn callback_registry() -> &'static Mutex<HashMap<(usize, RawFd), Registration>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<(usize, RawFd), Registration>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Trampoline matching `ALooper_callbackFunc`, reconstructing the boxed closure from `data` and
+/// freeing it when the closure asks to unregister.
+extern "C" fn fd_callback_trampoline(fd: c_int, events: c_int, data: *mut c_void) -> c_int {
+    let raw = data as *const Mutex<BoxedFdCallback>;
+    // Take a genuine strong reference for the duration of the call so teardown on another thread
+    // cannot free the allocation while the closure is running, then restore the native reference.
+    let shared: SharedFdCallback = unsafe {
+        let native = Arc::from_raw(raw);
+        let borrowed = native.clone();
+        let _ = Arc::into_raw(native);
+        borrowed
+    };
+    let keep = {
+        let mut callback = shared.lock().unwrap();
+        (&mut **callback)(fd as RawFd, FdEvent::from_bits_truncate(events))
+    };
+    if keep {
+        1
+    } else {
+        // Self-unregister: remove our entry and reclaim the native reference. Whoever removes the
+        // entry from the map (here or a concurrent `remove_fd`) does the reclaim exactly once.
+        let removed = {
+            let mut registry = callback_registry().lock().unwrap();
+            let key = registry
+                .iter()
+                .find(|&(_, registration)| registration.data == raw)
+                .map(|(key, _)| *key);
+            key.and_then(|key| registry.remove(&key))
+        };
+        if let Some(registration) = removed {
+            drop_registration(registration);
+        }
+        0
+    }
+}
+
 /// `RAII` acquired looper wrapper.
 ///
 /// This prevents the object from being deleted until this wrapper is dropped.
@@ -117,7 +500,7 @@ pub struct AcquiredLooper {
 
 impl AcquiredLooper {
     /// Acquire looper to prevent its deletion until this object is dropped.
-    pub fn from_ref(looper: LooperRef) -> AcquiredLooper {
+    pub fn from_ref(looper: ForeignLooper) -> AcquiredLooper {
         unsafe { ffi::ALooper_acquire(looper.handle()) }
         AcquiredLooper { handle: looper.handle() }
     }
@@ -128,3 +511,78 @@ impl Drop for AcquiredLooper {
         unsafe { ffi::ALooper_acquire(self.handle) }
     }
 }
+
+/// Names every `android_looper_sys` symbol this crate relies on beyond the baseline
+/// `ALooper_prepare`/`ALooper_pollAll`/`ALooper_acquire` surface, so a trimmed or stale sys binding
+/// fails to compile here with a single clear pointer instead of errors scattered across call sites.
+#[allow(dead_code)]
+mod ffi_surface {
+    use super::ffi;
+    use libc::c_int;
+
+    const _FOR_THREAD: unsafe extern "C" fn() -> *mut ffi::ALooper = ffi::ALooper_forThread;
+    const _POLL_ONCE: unsafe extern "C" fn(c_int, *mut c_int, *mut c_int, *mut *mut libc::c_void) -> c_int =
+        ffi::ALooper_pollOnce;
+    const _WAKE: unsafe extern "C" fn(*mut ffi::ALooper) = ffi::ALooper_wake;
+    const _REMOVE_FD: unsafe extern "C" fn(*mut ffi::ALooper, c_int) -> c_int = ffi::ALooper_removeFd;
+
+    const _POLL_CODES: [c_int; 4] = [
+        ffi::ALOOPER_POLL_WAKE,
+        ffi::ALOOPER_POLL_CALLBACK,
+        ffi::ALOOPER_POLL_TIMEOUT,
+        ffi::ALOOPER_POLL_ERROR,
+    ];
+    const _EVENT_FLAGS: [c_int; 5] = [
+        ffi::ALOOPER_EVENT_INPUT,
+        ffi::ALOOPER_EVENT_OUTPUT,
+        ffi::ALOOPER_EVENT_ERROR,
+        ffi::ALOOPER_EVENT_HANGUP,
+        ffi::ALOOPER_EVENT_INVALID,
+    ];
+
+    // `ALooper_addFd` takes an `ALooper_callbackFunc`; the trampoline's type must match that alias.
+    const _ADD_FD: unsafe extern "C" fn(
+        *mut ffi::ALooper,
+        c_int,
+        c_int,
+        c_int,
+        ffi::ALooper_callbackFunc,
+        *mut libc::c_void,
+    ) -> c_int = ffi::ALooper_addFd;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_to_millis_maps_edge_cases() {
+        // No timeout blocks forever.
+        assert_eq!(timeout_to_millis(None), -1);
+        // A zero duration is non-blocking.
+        assert_eq!(timeout_to_millis(Some(Duration::new(0, 0))), 0);
+        // Whole milliseconds pass through unchanged.
+        assert_eq!(timeout_to_millis(Some(Duration::from_millis(250))), 250);
+        // Sub-millisecond durations truncate towards zero.
+        assert_eq!(timeout_to_millis(Some(Duration::new(0, 999_999))), 0);
+        assert_eq!(timeout_to_millis(Some(Duration::new(0, 1_500_000))), 1);
+        // Durations beyond `i32::MAX` milliseconds saturate.
+        assert_eq!(
+            timeout_to_millis(Some(Duration::from_secs(60 * 60 * 24 * 365))),
+            c_int::MAX
+        );
+    }
+
+    #[test]
+    fn fd_event_flags_combine_and_truncate() {
+        let rw = FdEvent::INPUT | FdEvent::OUTPUT;
+        assert!(rw.contains(FdEvent::INPUT));
+        assert!(rw.contains(FdEvent::OUTPUT));
+        assert!(!rw.contains(FdEvent::ERROR));
+        assert_eq!(rw.bits(), FdEvent::INPUT.bits() | FdEvent::OUTPUT.bits());
+        // Unknown bits are dropped.
+        let truncated = FdEvent::from_bits_truncate(FdEvent::INPUT.bits() | (1 << 20));
+        assert_eq!(truncated, FdEvent::INPUT);
+        assert_eq!(FdEvent::empty().bits(), 0);
+    }
+}
@@ -0,0 +1,192 @@
+//! Cross-thread message passing into a looper thread.
+//!
+//! This builds a sender/receiver pair on top of a [`ForeignLooper`](super::ForeignLooper) by
+//! pairing a lock-free MPSC queue with an `eventfd`. Producer threads push a value onto the queue
+//! and write to the `eventfd`, which wakes the looper; the looper reports the descriptor as a
+//! `Poll::Event` under a caller-chosen identifier, and the consumer drains the queue with
+//! [`Receiver::try_recv`].
+//!
+//! The wrapper is zero-cost when unused: nothing polls or allocates until a channel is created.
+
+extern crate crossbeam_queue;
+
+use self::crossbeam_queue::SegQueue;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use libc::{self, c_int, c_void};
+
+use error::{Error, Result};
+use {FdEvent, ForeignLooper};
+
+/// A queued value, optionally carrying an acknowledgement handle for [`Sender::send_sync`].
+struct Message<T> {
+    value: T,
+    ack: Option<Arc<Ack>>,
+}
+
+/// One-shot notification used to block a synchronous sender until the looper side has finished.
+struct Ack {
+    done: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Ack {
+    fn new() -> Ack {
+        Ack { done: Mutex::new(false), condvar: Condvar::new() }
+    }
+
+    /// Block until [`notify`](#method.notify) is called.
+    fn wait(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.condvar.wait(done).unwrap();
+        }
+    }
+
+    /// Release any thread waiting in [`wait`](#method.wait).
+    fn notify(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// State shared between a [`Sender`] and its [`Receiver`].
+struct Shared<T> {
+    queue: SegQueue<Message<T>>,
+    eventfd: RawFd,
+}
+
+impl<T> Shared<T> {
+    /// Write to the `eventfd` to wake the looper.
+    fn notify(&self) -> Result<()> {
+        let value: u64 = 1;
+        let ret = unsafe {
+            libc::write(self.eventfd, &value as *const u64 as *const c_void, 8)
+        };
+        if ret == 8 {
+            Ok(())
+        } else {
+            Err(Error::EventFdFailed)
+        }
+    }
+
+    /// Read and reset the `eventfd` counter, discarding its value.
+    fn drain_eventfd(&self) {
+        let mut value: u64 = 0;
+        unsafe {
+            libc::read(self.eventfd, &mut value as *mut u64 as *mut c_void, 8);
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.eventfd); }
+    }
+}
+
+/// The sending half of a looper channel. Cloneable, and `Send + Sync` when `T` is `Send`.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Push `value` onto the queue and wake the looper so it can receive it.
+    pub fn send(&self, value: T) -> Result<()> {
+        self.shared.queue.push(Message { value: value, ack: None });
+        self.shared.notify()
+    }
+
+    /// Push `value` onto the queue, wake the looper, and block until the looper side has finished
+    /// processing it.
+    ///
+    /// The value is delivered together with an [`EventSyncGuard`]; this call only returns once
+    /// that guard has been dropped on the looper thread. Use it to transfer ownership of a handle
+    /// and guarantee the sender does not proceed until cleanup has completed, mirroring blocking
+    /// destruction callbacks. If waking the looper fails, the error is returned at once without
+    /// waiting; the value stays queued.
+    pub fn send_sync(&self, value: T) -> Result<()> {
+        let ack = Arc::new(Ack::new());
+        self.shared.queue.push(Message { value: value, ack: Some(ack.clone()) });
+        self.shared.notify()?;
+        ack.wait();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+/// The receiving half of a looper channel, living on the looper thread.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Register the channel's `eventfd` with `looper` under `ident`.
+    ///
+    /// Readiness then surfaces through `poll_once` as a `Poll::Event` carrying `ident`, at which
+    /// point the queue should be drained with [`try_recv`](#method.try_recv).
+    pub fn register(&self, looper: &ForeignLooper, ident: c_int) -> Result<()> {
+        looper.add_fd(self.shared.eventfd, ident, FdEvent::INPUT)
+    }
+
+    /// Remove the channel's `eventfd` from `looper`.
+    pub fn unregister(&self, looper: &ForeignLooper) -> Result<()> {
+        looper.remove_fd(self.shared.eventfd)
+    }
+
+    /// The underlying `eventfd`, exposed for callers that register it by hand.
+    pub fn eventfd(&self) -> RawFd {
+        self.shared.eventfd
+    }
+
+    /// Clear the `eventfd` counter and pop the next queued value, if any.
+    ///
+    /// Call this in a loop until it returns `None` to drain everything queued since the last poll.
+    /// The value is paired with an [`EventSyncGuard`]; dropping it releases a
+    /// [`Sender::send_sync`] caller blocked on this value (for plain [`Sender::send`] the guard is
+    /// inert). Hold the guard for as long as the sender must wait.
+    pub fn try_recv(&self) -> Option<(T, EventSyncGuard)> {
+        self.shared.drain_eventfd();
+        self.shared.queue.pop().map(|message| {
+            (message.value, EventSyncGuard { ack: message.ack })
+        })
+    }
+}
+
+/// Acknowledgement guard handed out alongside a received value.
+///
+/// When a value was sent with [`Sender::send_sync`], dropping this guard unblocks the sender. For
+/// values sent with [`Sender::send`] the guard carries no acknowledgement and dropping it does
+/// nothing.
+pub struct EventSyncGuard {
+    ack: Option<Arc<Ack>>,
+}
+
+impl Drop for EventSyncGuard {
+    fn drop(&mut self) {
+        if let Some(ref ack) = self.ack {
+            ack.notify();
+        }
+    }
+}
+
+/// Create a looper channel, allocating the backing `eventfd`.
+///
+/// Returns a cloneable [`Sender`] that any thread may use and a [`Receiver`] that the looper
+/// thread registers and drains.
+pub fn looper_channel<T>() -> Result<(Sender<T>, Receiver<T>)> {
+    let eventfd = unsafe {
+        libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC)
+    };
+    if eventfd == -1 {
+        return Err(Error::EventFdFailed);
+    }
+    let shared = Arc::new(Shared { queue: SegQueue::new(), eventfd: eventfd });
+    Ok((Sender { shared: shared.clone() }, Receiver { shared: shared }))
+}